@@ -1,21 +1,25 @@
-use crate::reactor::Reactor;
-use futures::{future::LocalBoxFuture, Future, FutureExt};
+use crate::reactor::{self, Reactor, TimerId};
+use futures::Future;
 use std::{
-    cell::RefCell,
+    alloc::Layout,
+    cell::{Cell, RefCell},
     collections::VecDeque,
     marker::PhantomData,
-    mem,
     pin::Pin,
+    ptr::{self, NonNull},
     rc::Rc,
-    task::{Context, RawWaker, RawWakerVTable, Waker},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    time::{Duration, Instant},
 };
+pub mod combinators;
+
 scoped_tls::scoped_thread_local!(pub(crate) static EX: Executor);
 
 /// `Executor`负责`Task`的调度和执行
 pub struct Executor {
     /// 等待调度的`Task`队列
     local_queue: TaskQueue,
-    pub(crate) reactor: Rc<RefCell<Reactor>>,
+    pub(crate) reactor: Rc<RefCell<Box<dyn Reactor>>>,
 
     /// Make sure the type is `!Send` and `!Sync`.
     _marker: PhantomData<Rc<()>>,
@@ -32,19 +36,37 @@ impl Executor {
     pub fn new() -> Self {
         Self {
             local_queue: TaskQueue::default(),
-            reactor: Rc::new(RefCell::new(Reactor::default())),
+            reactor: Rc::new(RefCell::new(reactor::new_reactor())),
 
             _marker: PhantomData,
         }
     }
 
     /// 一个`async fn`可以认为是一个`Future`
-    /// `spawn`将`Future`加入调度队列
-    pub fn spawn(fut: impl Future<Output = ()> + 'static) {
-        let t = Rc::new(Task {
-            future: RefCell::new(fut.boxed_local()),
+    /// `spawn`将`Future`加入调度队列，返回的`JoinHandle`可以拿到`Future`的结果。
+    /// `Task`的调度头和`Future`本身存放在同一块分配里，详见`RawTaskLayout`
+    pub fn spawn<F, T>(fut: F) -> JoinHandle<T>
+    where
+        F: Future<Output = T> + 'static,
+        T: 'static,
+    {
+        let inner = Rc::new(JoinInner {
+            output: RefCell::new(None),
+            waker: RefCell::new(None),
         });
-        EX.with(|ex| ex.local_queue.push(t));
+        let join_fut = JoinFuture {
+            fut,
+            inner: inner.clone(),
+        };
+
+        // 初始引用计数为2：一份给下面推入队列的`Runnable`，一份给返回的`JoinHandle`
+        let ptr = alloc_task(join_fut);
+        EX.with(|ex| ex.local_queue.push(Runnable(TaskRef { ptr })));
+
+        JoinHandle {
+            inner,
+            task: TaskRef { ptr },
+        }
     }
 
     /// 创建一个 dummy_waker，这个 waker 其实啥事不做。
@@ -69,12 +91,10 @@ impl Executor {
                     break t;
                 }
 
-                // consume all tasks
-                while let Some(t) = self.local_queue.pop() {
-                    let future = t.future.borrow_mut();
-                    let w = waker(t.clone());
-                    let mut context = Context::from_waker(&w);
-                    let _ = Pin::new(future).as_mut().poll(&mut context);
+                // consume all tasks; a cancelled task's `run` just reports done
+                // without polling, and the `Runnable` is dropped right after
+                while let Some(runnable) = self.local_queue.pop() {
+                    let _ = runnable.run();
                 }
 
                 // no task to execute now, it may ready
@@ -89,9 +109,62 @@ impl Executor {
     }
 }
 
+/// 等待一段时间后`Ready`的`Future`，由`Reactor`里的定时器堆驱动
+pub struct TimerFuture {
+    deadline: Instant,
+    timer_id: Option<TimerId>,
+}
+
+/// 创建一个在`dur`之后`Ready`的`Future`
+pub fn sleep(dur: Duration) -> TimerFuture {
+    TimerFuture {
+        deadline: Instant::now() + dur,
+        timer_id: None,
+    }
+}
+
+impl Future for TimerFuture {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        if self.timer_id.is_none() {
+            let deadline = self.deadline;
+            let id = EX.with(|ex| {
+                ex.reactor
+                    .borrow()
+                    .register_timer(deadline, cx.waker().clone())
+            });
+            self.timer_id = Some(id);
+        }
+
+        Poll::Pending
+    }
+}
+
+impl Drop for TimerFuture {
+    /// 如果`Future`在定时器触发前就被丢弃（比如`select`的败者分支），摘掉还留在堆里的条目，
+    /// 否则那个`Waker`会一直存活到原定的`deadline`，到时候唤醒一个已经没人在等待、甚至已经跑完的`Task`。
+    /// 被`cancel`的`Task`不会在取消时丢弃它的`Future`：堆里的`Waker`本身持有这个`Task`的一份引用，
+    /// 所以它的`TimerFuture`要等定时器触发后最后一份引用被释放（或者`Executor`被丢弃）时才随整块分配一起被丢弃。
+    /// `EX`只在`block_on`的调用期间有效：`Executor`自身连同还没触发的定时器一起被丢弃
+    /// （最普通的关闭路径，不需要等每个`sleep`都跑完）时，这里已经不在那个动态范围内了，
+    /// 这时候堆上的条目会随`reactor`一起释放，不用再手动摘掉，直接跳过即可
+    fn drop(&mut self) {
+        if let Some(id) = self.timer_id.take() {
+            if EX.is_set() {
+                EX.with(|ex| ex.reactor.borrow().deregister_timer(id));
+            }
+        }
+    }
+}
+
 /// 存储`Task`的队列
 pub struct TaskQueue {
-    queue: RefCell<VecDeque<Rc<Task>>>,
+    queue: RefCell<VecDeque<Runnable>>,
 }
 
 impl Default for TaskQueue {
@@ -115,77 +188,360 @@ impl TaskQueue {
     }
 
     /// 添加一个`Task`
-    pub(crate) fn push(&self, runnable: Rc<Task>) {
+    pub(crate) fn push(&self, runnable: Runnable) {
         println!("add task");
         self.queue.borrow_mut().push_back(runnable);
     }
 
     /// 删除第一个`Task`
-    pub(crate) fn pop(&self) -> Option<Rc<Task>> {
+    pub(crate) fn pop(&self) -> Option<Runnable> {
         println!("remove task");
         self.queue.borrow_mut().pop_front()
     }
 }
 
-/// `Task`是对`Future`的一个简单封装
-pub struct Task {
-    future: RefCell<LocalBoxFuture<'static, ()>>,
+/// `Task`的调度状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TaskState {
+    /// 正常参与调度
+    Running,
+    /// `Future`已经执行完毕
+    Completed,
+    /// 被取消，`Runnable::run`遇到时会直接跳过`poll`
+    Closed,
 }
 
-/// 创建一个和`Task`关联的`Waker`, 当`Task`准备好执行的时候, 调用`Waker`提供的`wake`和`wake_by_ref`方法
-fn waker(wake: Rc<Task>) -> Waker {
-    let ptr = Rc::into_raw(wake) as *const ();
-    let vtable = &Helper::VTABLE;
-    unsafe { Waker::from_raw(RawWaker::new(ptr, vtable)) }
+/// `JoinHandle`和它对应的`Task`共享的状态：存放结果的槽位，以及等待结果的`Waker`
+struct JoinInner<T> {
+    output: RefCell<Option<T>>,
+    waker: RefCell<Option<Waker>>,
 }
 
-impl Task {
-    /// 唤醒`Task`, 添加到调度队列中等待调度
-    fn wake_(self: Rc<Self>) {
-        Self::wake_by_ref_(&self)
+/// 包在用户`Future`外面驱动它的`Future`：`Output`到了就写进`JoinInner`并唤醒`JoinHandle`。
+/// 直接内联`fut`而不是`Box`它，这样`Task`的分配里就不会再套一层堆分配
+struct JoinFuture<F: Future> {
+    fut: F,
+    inner: Rc<JoinInner<F::Output>>,
+}
+
+impl<F: Future> Future for JoinFuture<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // `fut`和它所在的这份分配一样，在第一次poll之后就不会再被移动，满足结构化固定的前提
+        let this = unsafe { self.get_unchecked_mut() };
+        let fut = unsafe { Pin::new_unchecked(&mut this.fut) };
+        match fut.poll(cx) {
+            Poll::Ready(v) => {
+                *this.inner.output.borrow_mut() = Some(v);
+                if let Some(w) = this.inner.waker.borrow_mut().take() {
+                    w.wake();
+                }
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
     }
+}
 
-    /// 唤醒`Task`, 添加到调度队列中等待调度
-    fn wake_by_ref_(self: &Rc<Self>) {
-        EX.with(|ex| ex.local_queue.push(self.clone()));
+/// `spawn`返回的句柄，自身是一个`Future`，`poll`它可以拿到对应`Task`的输出。
+/// 如果`Task`被取消，则`poll`的结果是`Poll::Ready(None)`。
+pub struct JoinHandle<T> {
+    inner: Rc<JoinInner<T>>,
+    task: TaskRef,
+}
+
+impl<T> JoinHandle<T> {
+    /// 取消对应的`Task`：标记为`Closed`，下次被调度到时会直接跳过`poll`，
+    /// 正在`await`这个句柄的话会得到`None`。
+    /// 和`Drop`一样只取消还在`Running`的`Task`：如果`Future`已经跑完，输出已经
+    /// 躺在`JoinInner::output`里等着被取走，这时候`cancel`不应该把它连同`Closed`
+    /// 状态一起冲掉，否则`poll`会先看到`Closed`而把已经算出来的结果丢在原地
+    pub fn cancel(&self) {
+        if self.task.state() == TaskState::Running {
+            self.task.set_state(TaskState::Closed);
+        }
+        if let Some(w) = self.inner.waker.borrow_mut().take() {
+            w.wake();
+        }
     }
 }
 
-struct Helper;
+impl<T> Future for JoinHandle<T> {
+    type Output = Option<T>;
 
-impl Helper {
-    const VTABLE: RawWakerVTable = RawWakerVTable::new(
-        Self::clone_waker,
-        Self::wake,
-        Self::wake_by_ref,
-        Self::drop_waker,
-    );
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        if self.task.state() == TaskState::Closed {
+            return Poll::Ready(None);
+        }
+        if let Some(v) = self.inner.output.borrow_mut().take() {
+            Poll::Ready(Some(v))
+        } else {
+            *self.inner.waker.borrow_mut() = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
 
-    unsafe fn clone_waker(data: *const ()) -> RawWaker {
-        increase_refcount(data);
-        let vtable = &Self::VTABLE;
-        RawWaker::new(data, vtable)
+impl<T> Drop for JoinHandle<T> {
+    /// 丢弃`JoinHandle`时，如果对应的`Task`还在运行就取消它
+    fn drop(&mut self) {
+        if self.task.state() == TaskState::Running {
+            self.task.set_state(TaskState::Closed);
+        }
     }
+}
+
+// ---- 单次分配的Task：Header和Future挨在一起，`RawWaker`直接指向这块内存 ----
+// 布局和引用计数管理都仿照`async-task`：一个`Task`只分配一次，`Waker`的clone/drop
+// 只是在原地增减`TaskHeader::ref_count`，不会再触发额外的堆操作。
+
+/// `Task`分配的头部：调度状态、引用计数，以及指向具体`Future`类型操作的虚表
+#[repr(C)]
+struct TaskHeader {
+    ref_count: Cell<usize>,
+    state: Cell<TaskState>,
+    vtable: &'static TaskVTable,
+}
+
+/// 依赖具体`Future`类型`F`的操作，由`Ops`按`F`单态化后存成`'static`的虚表
+struct TaskVTable {
+    /// 执行一次`poll`；返回`false`表示`Future`已经完成或者`Task`已被取消
+    run: unsafe fn(*const TaskHeader) -> bool,
+    /// 引用计数归零时，负责`drop`内联的`Future`并释放整块分配
+    dealloc: unsafe fn(*const TaskHeader),
+}
+
+/// `Task`实际的内存布局：`Header`后面紧跟着内联的`Future`
+#[repr(C)]
+struct RawTaskLayout<F> {
+    header: TaskHeader,
+    future: RefCell<F>,
+}
+
+/// 按`F`生成一份`TaskVTable`，零大小的辅助类型，只用来挂关联常量和关联函数
+struct Ops<F>(PhantomData<F>);
+
+impl<F: Future<Output = ()> + 'static> Ops<F> {
+    const VTABLE: TaskVTable = TaskVTable {
+        run: Self::run,
+        dealloc: Self::dealloc,
+    };
+
+    unsafe fn run(ptr: *const TaskHeader) -> bool {
+        // `Closed`是被取消跳过的，`Completed`是`Future`已经返回过`Ready`：
+        // 两种状态都不能再`poll`一次，否则要么违反取消语义，要么让生成的状态机在
+        // 完成之后又被`resume`一次而直接panic（比如被丢弃前落在定时器堆里的那个
+        // 唤醒，在`Future`早已`Ready`之后才姗姗来迟地触发）
+        if matches!((*ptr).state.get(), TaskState::Closed | TaskState::Completed) {
+            return false;
+        }
+
+        // 这次增加的引用对应下面构造的临时`Waker`；如果`Future`内部把它克隆并存了起来
+        // （比如注册进`Reactor`），对应的引用就会在`Waker`被丢弃后继续存活
+        increase_refcount(ptr);
+        let waker = Waker::from_raw(RawWaker::new(ptr as *const (), &RAW_WAKER_VTABLE));
+        let mut cx = Context::from_waker(&waker);
 
-    unsafe fn wake(ptr: *const ()) {
-        let rc = Rc::from_raw(ptr as *const Task);
-        rc.wake_();
+        let layout_ptr = ptr as *const RawTaskLayout<F>;
+        let mut future = (*layout_ptr).future.borrow_mut();
+        let pinned = Pin::new_unchecked(&mut *future);
+        match pinned.poll(&mut cx) {
+            Poll::Ready(()) => {
+                (*ptr).state.set(TaskState::Completed);
+                false
+            }
+            Poll::Pending => true,
+        }
+    }
+
+    unsafe fn dealloc(ptr: *const TaskHeader) {
+        let layout_ptr = ptr as *mut RawTaskLayout<F>;
+        ptr::drop_in_place(layout_ptr);
+        std::alloc::dealloc(layout_ptr as *mut u8, Layout::new::<RawTaskLayout<F>>());
+    }
+}
+
+/// 分配一个新的`Task`，初始引用计数为2（调用者要自己创建两份`TaskRef`承接）
+fn alloc_task<F: Future<Output = ()> + 'static>(future: F) -> NonNull<TaskHeader> {
+    let layout = Layout::new::<RawTaskLayout<F>>();
+    unsafe {
+        let raw = std::alloc::alloc(layout) as *mut RawTaskLayout<F>;
+        assert!(!raw.is_null(), "task allocation failed");
+        ptr::write(
+            raw,
+            RawTaskLayout {
+                header: TaskHeader {
+                    ref_count: Cell::new(2),
+                    state: Cell::new(TaskState::Running),
+                    vtable: &Ops::<F>::VTABLE,
+                },
+                future: RefCell::new(future),
+            },
+        );
+        NonNull::new_unchecked(raw as *mut TaskHeader)
+    }
+}
+
+unsafe fn increase_refcount(ptr: *const TaskHeader) {
+    let header = &*ptr;
+    header.ref_count.set(header.ref_count.get() + 1);
+}
+
+/// 减少一次引用计数，归零时调用虚表里的`dealloc`释放整块分配
+unsafe fn drop_task_ref(ptr: *const TaskHeader) {
+    let header = &*ptr;
+    let count = header.ref_count.get() - 1;
+    header.ref_count.set(count);
+    if count == 0 {
+        (header.vtable.dealloc)(ptr);
+    }
+}
+
+/// 对`Task`分配的一次强引用，`Clone`走`increase_refcount`，`Drop`走`drop_task_ref`
+struct TaskRef {
+    ptr: NonNull<TaskHeader>,
+}
+
+impl TaskRef {
+    fn header(&self) -> &TaskHeader {
+        unsafe { self.ptr.as_ref() }
     }
 
-    unsafe fn wake_by_ref(ptr: *const ()) {
-        let rc = mem::ManuallyDrop::new(Rc::from_raw(ptr as *const Task));
-        rc.wake_by_ref_();
+    fn state(&self) -> TaskState {
+        self.header().state.get()
     }
 
-    unsafe fn drop_waker(ptr: *const ()) {
-        drop(Rc::from_raw(ptr as *const Task));
+    fn set_state(&self, state: TaskState) {
+        self.header().state.set(state)
+    }
+}
+
+impl Drop for TaskRef {
+    fn drop(&mut self) {
+        unsafe { drop_task_ref(self.ptr.as_ptr()) };
     }
 }
 
-#[allow(clippy::redundant_clone)] // The clone here isn't actually redundant.
-unsafe fn increase_refcount(data: *const ()) {
-    // Retain Rc, but don't touch refcount by wrapping in ManuallyDrop
-    let rc = mem::ManuallyDrop::new(Rc::<Task>::from_raw(data as *const Task));
-    // Now increase refcount, but don't drop new refcount either
-    let _rc_clone: mem::ManuallyDrop<_> = rc.clone();
+/// 从`TaskQueue`里弹出的句柄，多一个`run`方法可以真正执行一次`poll`
+pub(crate) struct Runnable(TaskRef);
+
+impl Runnable {
+    /// 执行一次`poll`，返回`Task`是否仍未结束。
+    /// 不管结果如何，`self`持有的这一份引用都会在函数返回时被`Drop`释放
+    fn run(self) -> bool {
+        unsafe { (self.0.header().vtable.run)(self.0.ptr.as_ptr()) }
+    }
+}
+
+static RAW_WAKER_VTABLE: RawWakerVTable =
+    RawWakerVTable::new(raw_clone, raw_wake, raw_wake_by_ref, raw_drop);
+
+unsafe fn raw_clone(data: *const ()) -> RawWaker {
+    increase_refcount(data as *const TaskHeader);
+    RawWaker::new(data, &RAW_WAKER_VTABLE)
+}
+
+unsafe fn raw_wake(data: *const ()) {
+    // 消费掉这一份引用：直接转成`Runnable`塞回调度队列
+    let ptr = NonNull::new_unchecked(data as *mut TaskHeader);
+    EX.with(|ex| ex.local_queue.push(Runnable(TaskRef { ptr })));
+}
+
+unsafe fn raw_wake_by_ref(data: *const ()) {
+    increase_refcount(data as *const TaskHeader);
+    raw_wake(data);
+}
+
+unsafe fn raw_drop(data: *const ()) {
+    drop_task_ref(data as *const TaskHeader);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 第一次`poll`返回`Pending`并立刻自唤醒，第二次才`Ready`：逼`Ops::run`真正走一遍
+    /// `increase_refcount` -> 构造临时`Waker` -> `wake_by_ref`又clone一次 -> 两次`Drop`的完整路径
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = u32;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<u32> {
+            if self.0 {
+                Poll::Ready(42)
+            } else {
+                self.0 = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    #[test]
+    fn spawned_task_survives_multiple_polls_and_hands_back_its_output() {
+        let ex = Executor::new();
+        let result = ex.block_on(|| async { Executor::spawn(YieldOnce(false)).await });
+        assert_eq!(result, Some(42));
+    }
+
+    #[test]
+    fn cancel_after_completion_does_not_discard_the_result() {
+        let ex = Executor::new();
+        let result = ex.block_on(|| async {
+            let handle = Executor::spawn(async { 42 });
+            // 借`YieldOnce`逼一次`poll`，让队列排空阶段把spawn出来的任务真正跑完，
+            // 这样下面的`cancel`发生在`Task`已经`Completed`之后
+            let _ = YieldOnce(false).await;
+            handle.cancel();
+            handle.await
+        });
+        assert_eq!(result, Some(42));
+    }
+
+    #[test]
+    fn cancel_before_first_poll_skips_the_future_entirely() {
+        let ex = Executor::new();
+        let polled = Rc::new(Cell::new(false));
+        let polled_in_task = polled.clone();
+        let result = ex.block_on(move || {
+            let polled_in_task = polled_in_task.clone();
+            async move {
+                let handle = Executor::spawn(async move {
+                    polled_in_task.set(true);
+                });
+                handle.cancel();
+                handle.await
+            }
+        });
+        assert_eq!(result, None);
+        assert!(
+            !polled.get(),
+            "a cancelled task's future must never be polled"
+        );
+    }
+
+    #[test]
+    fn dropping_executor_with_a_pending_timer_does_not_panic() {
+        let ex = Executor::new();
+        let slot: Rc<RefCell<Option<JoinHandle<()>>>> = Rc::new(RefCell::new(None));
+        let slot_in_task = slot.clone();
+        ex.block_on(move || {
+            let slot_in_task = slot_in_task.clone();
+            async move {
+                let h = Executor::spawn(async {
+                    sleep(Duration::from_secs(100)).await;
+                });
+                // 逼一次`poll`，让排空队列的那一步真正跑一次`Task`，把定时器注册进反应堆，
+                // 这样下面丢弃这个句柄时，还有未触发的定时器挂在反应堆里——也是最普通的
+                // 关闭路径，没人规定每个`sleep`都要等到触发才能退出
+                let _ = YieldOnce(false).await;
+                *slot_in_task.borrow_mut() = Some(h);
+            }
+        });
+        let handle = slot.borrow_mut().take();
+        drop(handle);
+        drop(ex);
+    }
 }
@@ -0,0 +1,207 @@
+//! 不依赖`spawn`的本地并发组合子：`join`等两个子`Future`都完成，`select`等其中一个先完成。
+//! 两者每次`poll`都会把`Context`转发给还没完成的子`Future`，好让它们能重新向反应堆注册`Waker`。
+
+use futures::{ready, Future};
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// 一个子`Future`自身的进度：还没完成、已经有结果在等着被取走、或者结果已经被取走
+enum MaybeDone<F: Future> {
+    Pending(F),
+    Done(F::Output),
+    Taken,
+}
+
+impl<F: Future> MaybeDone<F> {
+    /// 推进一次；已经`Done`就直接报告完成，不会重复`poll`内部的`F`
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = unsafe { self.get_unchecked_mut() };
+        match this {
+            MaybeDone::Pending(fut) => {
+                let fut = unsafe { Pin::new_unchecked(fut) };
+                let output = ready!(fut.poll(cx));
+                *this = MaybeDone::Done(output);
+                Poll::Ready(())
+            }
+            MaybeDone::Done(_) => Poll::Ready(()),
+            MaybeDone::Taken => unreachable!("MaybeDone polled again after output was taken"),
+        }
+    }
+
+    /// 取走已经就绪的结果，只应该在`poll`报告`Ready`之后调用一次
+    fn take(self: Pin<&mut Self>) -> F::Output {
+        let this = unsafe { self.get_unchecked_mut() };
+        match mem::replace(this, MaybeDone::Taken) {
+            MaybeDone::Done(output) => output,
+            _ => panic!("MaybeDone::take called before the future completed"),
+        }
+    }
+}
+
+/// `join(a, b)`返回的`Future`：两个子`Future`各自推进，直到都`Ready`才整体`Ready`
+pub struct Join<A: Future, B: Future> {
+    a: MaybeDone<A>,
+    b: MaybeDone<B>,
+}
+
+/// 同时驱动`a`和`b`，等两者都完成后返回`(a, b)`各自的输出
+pub fn join<A, B>(a: A, b: B) -> Join<A, B>
+where
+    A: Future,
+    B: Future,
+{
+    Join {
+        a: MaybeDone::Pending(a),
+        b: MaybeDone::Pending(b),
+    }
+}
+
+impl<A: Future, B: Future> Future for Join<A, B> {
+    type Output = (A::Output, B::Output);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = unsafe { self.get_unchecked_mut() };
+        let a_done = unsafe { Pin::new_unchecked(&mut this.a) }
+            .poll(cx)
+            .is_ready();
+        let b_done = unsafe { Pin::new_unchecked(&mut this.b) }
+            .poll(cx)
+            .is_ready();
+
+        if a_done && b_done {
+            let a = unsafe { Pin::new_unchecked(&mut this.a) }.take();
+            let b = unsafe { Pin::new_unchecked(&mut this.b) }.take();
+            Poll::Ready((a, b))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// `select(a, b)`的结果：究竟是`a`还是`b`先完成
+pub enum Either<A, B> {
+    Left(A),
+    Right(B),
+}
+
+/// `select(a, b)`返回的`Future`：谁先`Ready`就返回谁的输出，另一个随`Select`一起被丢弃
+pub struct Select<A, B> {
+    a: A,
+    b: B,
+}
+
+/// 同时驱动`a`和`b`，哪个先完成就返回哪个的结果，没完成的那个不会再被`poll`
+pub fn select<A, B>(a: A, b: B) -> Select<A, B>
+where
+    A: Future,
+    B: Future,
+{
+    Select { a, b }
+}
+
+impl<A: Future, B: Future> Future for Select<A, B> {
+    type Output = Either<A::Output, B::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = unsafe { self.get_unchecked_mut() };
+
+        let a = unsafe { Pin::new_unchecked(&mut this.a) };
+        if let Poll::Ready(output) = a.poll(cx) {
+            return Poll::Ready(Either::Left(output));
+        }
+
+        let b = unsafe { Pin::new_unchecked(&mut this.b) };
+        if let Poll::Ready(output) = b.poll(cx) {
+            return Poll::Ready(Either::Right(output));
+        }
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// 前`delay`次`poll`返回`Pending`并自唤醒，之后`Ready(value)`；`polls`记录总共被`poll`了几次，
+    /// 供测试在子`Future`被移进组合子之后还能观察到它是否被重复`poll`
+    struct CountingDelayed<T> {
+        remaining: u32,
+        value: Option<T>,
+        polls: Rc<Cell<u32>>,
+    }
+
+    impl<T> CountingDelayed<T> {
+        fn new(delay: u32, value: T, polls: Rc<Cell<u32>>) -> Self {
+            Self {
+                remaining: delay,
+                value: Some(value),
+                polls,
+            }
+        }
+    }
+
+    impl<T: Unpin> Future for CountingDelayed<T> {
+        type Output = T;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+            let this = self.get_mut();
+            this.polls.set(this.polls.get() + 1);
+            if this.remaining == 0 {
+                Poll::Ready(this.value.take().expect("polled again after completion"))
+            } else {
+                this.remaining -= 1;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    /// 不依赖`Reactor`，用一个什么都不做的`Waker`反复`poll`直到`Ready`
+    fn block_on_noop<F: Future + Unpin>(mut fut: F) -> F::Output {
+        let waker = waker_fn::waker_fn(|| {});
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(v) = Pin::new(&mut fut).poll(&mut cx) {
+                return v;
+            }
+        }
+    }
+
+    #[test]
+    fn select_prefers_the_left_future_when_both_are_ready_on_the_same_poll() {
+        let polls = Rc::new(Cell::new(0));
+        let a = CountingDelayed::new(0, "a", polls.clone());
+        let b = CountingDelayed::new(0, "b", polls.clone());
+        let result = block_on_noop(select(a, b));
+        assert!(matches!(result, Either::Left("a")));
+    }
+
+    #[test]
+    fn select_returns_whichever_side_finishes_first() {
+        let polls = Rc::new(Cell::new(0));
+        let slow = CountingDelayed::new(3, "slow", polls.clone());
+        let fast = CountingDelayed::new(0, "fast", polls.clone());
+        let result = block_on_noop(select(slow, fast));
+        assert!(matches!(result, Either::Right("fast")));
+    }
+
+    #[test]
+    fn join_waits_for_both_and_never_repolls_the_side_that_finished_early() {
+        let a_polls = Rc::new(Cell::new(0));
+        let b_polls = Rc::new(Cell::new(0));
+        let a = CountingDelayed::new(0, 1u32, a_polls.clone());
+        let b = CountingDelayed::new(2, 2u32, b_polls.clone());
+
+        let (x, y) = block_on_noop(join(a, b));
+
+        assert_eq!((x, y), (1, 2));
+        // `a`在第一次`poll`就完成了；之后`Join`还要再poll几次`b`，但`MaybeDone::Done`
+        // 不应该再把这些poll转发给已经完成的`a`
+        assert_eq!(a_polls.get(), 1);
+        assert_eq!(b_polls.get(), 3);
+    }
+}
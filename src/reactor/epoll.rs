@@ -0,0 +1,131 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io,
+    os::unix::io::RawFd,
+    task::Waker,
+    time::Instant,
+};
+
+use super::{fd_token, IoOp, Reactor, TimerId, TimerWheel, Token};
+
+/// 基于`epoll`的I/O反应堆：注册fd关心的事件、在`wait`里阻塞直到有事件就绪或者定时器到期，
+/// 然后唤醒对应`Task`的`Waker`。就绪模型下buffer一直留在调用者手里，反应堆只负责"告诉你可以读写了"
+pub(crate) struct EpollReactor {
+    epoll_fd: RawFd,
+    waker_mapping: RefCell<HashMap<Token, Waker>>,
+    /// 每个fd当前真正向内核注册了的事件位（不含`EPOLLET`）。`epoll_ctl`是按fd注册的，
+    /// 但读写两个方向各自独立地感兴趣，所以只在这里记下调用者实际要等的那些位：
+    /// 第一次见到这个fd用`ADD`，之后不管是同方向重复注册还是另一个方向并发注册都叠加成`MOD`。
+    /// 如果不管调用者要哪个方向、每次都无脑订阅`EPOLLIN | EPOLLOUT`，没被订阅的那个方向的
+    /// 边沿会在没有对应`Waker`时被`wait`悄悄丢弃——而`EPOLLET`下`MOD`又不会对没有状态变化的
+    /// 方向重新生成通知，那个方向的`Future`就再也等不到它了
+    interests: RefCell<HashMap<RawFd, u32>>,
+    timers: TimerWheel,
+}
+
+impl EpollReactor {
+    /// 创建一个新的`EpollReactor`，底层对应一个`epoll`实例
+    pub(crate) fn new() -> Self {
+        let epoll_fd = unsafe { libc::epoll_create1(0) };
+        assert!(
+            epoll_fd >= 0,
+            "epoll_create1 failed: {}",
+            io::Error::last_os_error()
+        );
+        Self {
+            epoll_fd,
+            waker_mapping: RefCell::new(HashMap::new()),
+            interests: RefCell::new(HashMap::new()),
+            timers: TimerWheel::new(),
+        }
+    }
+}
+
+impl Reactor for EpollReactor {
+    /// 向`epoll`注册`op`涉及的fd，buffer本身对就绪模型没有意义，直接丢弃。
+    /// `token`按读写方向区分（见[`fd_token`]），但`epoll_ctl`是按fd注册的：只把这次调用者
+    /// 实际关心的那个方向的位叠加进已有的订阅，`ADD`一个新fd或者`MOD`一个已经在订阅别的
+    /// 方向的fd，绝不多订阅调用者没要求的方向
+    fn submit(&self, op: IoOp, token: Token, waker: Waker) -> io::Result<()> {
+        let (fd, want) = match op {
+            IoOp::Read { fd, .. } => (fd, libc::EPOLLIN as u32),
+            IoOp::Write { fd, .. } => (fd, libc::EPOLLOUT as u32),
+        };
+
+        self.waker_mapping.borrow_mut().insert(token, waker);
+        let mut interests = self.interests.borrow_mut();
+        let previous = interests.get(&fd).copied();
+        let events = previous.unwrap_or(0) | want;
+        let mut event = libc::epoll_event {
+            events: events | libc::EPOLLET as u32,
+            u64: fd as u64,
+        };
+        let op_code = if previous.is_some() {
+            libc::EPOLL_CTL_MOD
+        } else {
+            libc::EPOLL_CTL_ADD
+        };
+        let ret = unsafe { libc::epoll_ctl(self.epoll_fd, op_code, fd, &mut event) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        interests.insert(fd, events);
+        Ok(())
+    }
+
+    fn register_timer(&self, deadline: Instant, waker: Waker) -> TimerId {
+        self.timers.register(deadline, waker)
+    }
+
+    fn deregister_timer(&self, id: TimerId) {
+        self.timers.cancel(id);
+    }
+
+    /// 阻塞直到至少一个fd就绪或者一个定时器到期，然后唤醒对应的`Task`，推回`local_queue`
+    fn wait(&self) {
+        let timeout_ms = match self.timers.next_timeout() {
+            None => -1,
+            Some(d) => d.as_millis().min(i32::MAX as u128) as i32,
+        };
+
+        let mut events = vec![libc::epoll_event { events: 0, u64: 0 }; 1024];
+        let n = unsafe {
+            libc::epoll_wait(
+                self.epoll_fd,
+                events.as_mut_ptr(),
+                events.len() as i32,
+                timeout_ms,
+            )
+        };
+
+        if n > 0 {
+            let mut mapping = self.waker_mapping.borrow_mut();
+            for event in &events[..n as usize] {
+                // `event.u64`存的是fd本身，而不是某一次`submit`传进来的token：同一个fd上
+                // 读写两个方向可能各自挂着一个等待中的`Waker`，要按就绪的事件位分别找对应的token
+                let fd = event.u64 as RawFd;
+                if event.events & (libc::EPOLLIN as u32) != 0 {
+                    if let Some(waker) = mapping.remove(&fd_token(fd, false)) {
+                        waker.wake();
+                    }
+                }
+                if event.events & (libc::EPOLLOUT as u32) != 0 {
+                    if let Some(waker) = mapping.remove(&fd_token(fd, true)) {
+                        waker.wake();
+                    }
+                }
+            }
+        }
+
+        self.timers.fire_expired();
+    }
+}
+
+impl Drop for EpollReactor {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.epoll_fd);
+        }
+    }
+}
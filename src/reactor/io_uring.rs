@@ -0,0 +1,133 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io,
+    task::Waker,
+    time::{Duration, Instant},
+};
+
+use io_uring::{opcode, types, IoUring};
+
+use super::{CompletedIo, IoOp, Reactor, TimerId, TimerWheel, Token};
+
+const ENTRIES: u32 = 256;
+
+/// 基于`io_uring`的I/O反应堆：提交SQE而不是询问就绪状态，CQE到达时操作已经由内核完成，
+/// 连读写的buffer也一并还给调用者，省掉`epoll`模型下readiness之后那次额外的`read`/`write`系统调用
+pub(crate) struct IoUringReactor {
+    ring: RefCell<IoUring>,
+    waker_mapping: RefCell<HashMap<Token, Waker>>,
+    /// 提交之后、CQE到达之前，buffer的所有权暂存在这里
+    inflight: RefCell<HashMap<Token, Vec<u8>>>,
+    /// CQE到达之后，结果和buffer在这里等`take_completed`取走
+    completed: RefCell<HashMap<Token, CompletedIo>>,
+    timers: TimerWheel,
+}
+
+impl IoUringReactor {
+    pub(crate) fn new() -> Self {
+        let ring = IoUring::new(ENTRIES).expect("failed to create io_uring instance");
+        Self {
+            ring: RefCell::new(ring),
+            waker_mapping: RefCell::new(HashMap::new()),
+            inflight: RefCell::new(HashMap::new()),
+            completed: RefCell::new(HashMap::new()),
+            timers: TimerWheel::new(),
+        }
+    }
+}
+
+impl Reactor for IoUringReactor {
+    /// 把`op`转换成一个SQE提交给内核，buffer的所有权转移给`inflight`，直到CQE到达前都不能再碰它
+    fn submit(&self, op: IoOp, token: Token, waker: Waker) -> io::Result<()> {
+        self.waker_mapping.borrow_mut().insert(token, waker);
+
+        let entry = match op {
+            IoOp::Read { fd, mut buf } => {
+                let ptr = buf.as_mut_ptr();
+                let len = buf.len() as u32;
+                self.inflight.borrow_mut().insert(token, buf);
+                opcode::Read::new(types::Fd(fd), ptr, len)
+                    .build()
+                    .user_data(token as u64)
+            }
+            IoOp::Write { fd, buf } => {
+                let ptr = buf.as_ptr();
+                let len = buf.len() as u32;
+                self.inflight.borrow_mut().insert(token, buf);
+                opcode::Write::new(types::Fd(fd), ptr, len)
+                    .build()
+                    .user_data(token as u64)
+            }
+        };
+
+        let mut ring = self.ring.borrow_mut();
+        unsafe {
+            ring.submission()
+                .push(&entry)
+                .map_err(|_| io::Error::other("io_uring submission queue full"))?;
+        }
+        Ok(())
+    }
+
+    fn take_completed(&self, token: Token) -> Option<CompletedIo> {
+        self.completed.borrow_mut().remove(&token)
+    }
+
+    fn register_timer(&self, deadline: Instant, waker: Waker) -> TimerId {
+        self.timers.register(deadline, waker)
+    }
+
+    fn deregister_timer(&self, id: TimerId) {
+        self.timers.cancel(id);
+    }
+
+    /// 提交已入队的SQE并等待至少一个CQE，把完成的操作搬进`completed`，然后唤醒对应的`Task`
+    fn wait(&self) {
+        // `submit_and_wait`在堆里还有更早的定时器时不应该无限阻塞，但`io_uring`的超时需要额外的
+        // timeout SQE来配合；这里退化成定时器为空时才无限等待。有定时器时如果还是传0，
+        // `submit_and_wait`会立刻返回——`block_on`的主循环没有可跑的任务时会不停调用`wait`，
+        // 于是变成对着空的完成队列100%CPU忙等。退化成：先非阻塞地收一轮已经完成的I/O，
+        // 真的什么都没有才睡到最近的`deadline`（设个10ms的上限，免得睡眠期间到达的CQE要等到`deadline`才被处理）
+        let mut ring = self.ring.borrow_mut();
+        match self.timers.next_timeout() {
+            None => {
+                let _ = ring.submit_and_wait(1);
+            }
+            Some(_) => {
+                let _ = ring.submit_and_wait(0);
+                if ring.completion().is_empty() {
+                    if let Some(timeout) = self.timers.next_timeout() {
+                        drop(ring);
+                        std::thread::sleep(timeout.min(Duration::from_millis(10)));
+                        ring = self.ring.borrow_mut();
+                    }
+                }
+            }
+        }
+
+        let mut waker_mapping = self.waker_mapping.borrow_mut();
+        let mut inflight = self.inflight.borrow_mut();
+        let mut completed = self.completed.borrow_mut();
+        for cqe in ring.completion() {
+            let token = cqe.user_data() as Token;
+            let res = cqe.result();
+            let buf = inflight.remove(&token).unwrap_or_default();
+            let result = if res < 0 {
+                Err(io::Error::from_raw_os_error(-res))
+            } else {
+                Ok(res as usize)
+            };
+            completed.insert(token, (result, buf));
+            if let Some(waker) = waker_mapping.remove(&token) {
+                waker.wake();
+            }
+        }
+        drop(waker_mapping);
+        drop(inflight);
+        drop(completed);
+        drop(ring);
+
+        self.timers.fire_expired();
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::BinaryHeap,
+    io,
+    os::unix::io::RawFd,
+    task::Waker,
+    time::{Duration, Instant},
+};
+
+mod epoll;
+
+#[cfg(feature = "io_uring")]
+mod io_uring;
+
+/// 一个I/O操作在反应堆里对应的token，直接用它定位`waker_mapping`/`completed`里的条目
+pub(crate) type Token = usize;
+
+/// 同一个fd上读和写各自独立的token：`epoll`模型下一个fd只有一次`epoll_ctl`注册，
+/// 但读和写两个方向可能各自挂着一个不同的`Waker`在等待，必须用不同的token区分，
+/// 否则`join`之类的组合子并发读写同一个fd时后注册的一方会覆盖`waker_mapping`里先注册的那个条目
+pub(crate) fn fd_token(fd: RawFd, write: bool) -> Token {
+    ((fd as Token) << 1) | (write as Token)
+}
+
+/// 定时器的唯一标识，在`deadline`相同时用来区分堆里的两个条目
+pub(crate) type TimerId = u64;
+
+/// `io_uring`后端里一次完成的I/O操作：系统调用的结果，以及物归原主的buffer
+pub(crate) type CompletedIo = (io::Result<usize>, Vec<u8>);
+
+/// 一次异步I/O请求：`epoll`后端只需要知道对哪个fd的哪类事件感兴趣，buffer原样放在调用者手里；
+/// `io_uring`后端则需要拿到buffer的所有权才能提交对应的SQE
+pub(crate) enum IoOp {
+    Read { fd: RawFd, buf: Vec<u8> },
+    Write { fd: RawFd, buf: Vec<u8> },
+}
+
+/// 反应堆需要提供的能力：提交一次I/O操作、等待事件/完成、管理定时器。
+/// `epoll`是就绪模型（`take_completed`恒为`None`，调用者自己重试系统调用），
+/// `io_uring`是完成模型（`take_completed`会给出内核已经写好的结果和buffer）
+pub(crate) trait Reactor {
+    /// 提交一次I/O操作，`token`在操作完成/就绪时用来找到对应的`Waker`
+    fn submit(&self, op: IoOp, token: Token, waker: Waker) -> io::Result<()>;
+
+    /// 取走一次已经由内核完成的操作结果，就绪模型的后端永远返回`None`
+    fn take_completed(&self, _token: Token) -> Option<CompletedIo> {
+        None
+    }
+
+    /// 注册一个在`deadline`到期时需要被唤醒的定时器
+    fn register_timer(&self, deadline: Instant, waker: Waker) -> TimerId;
+
+    /// 撤销一个还没到期的定时器，对应的`Future`在触发前就被丢弃时调用（比如`select`的败者分支），
+    /// 避免它持有的`Waker`一直存活到原定的`deadline`才唤醒一个已经没人在等待的`Task`。
+    /// 被取消的`Task`不会因取消而丢弃它的`Future`，所以不会走到这里，它的定时器照常留到触发
+    fn deregister_timer(&self, id: TimerId);
+
+    /// 阻塞直到至少一个操作就绪/完成，或者一个定时器到期，然后唤醒对应的`Task`
+    fn wait(&self);
+}
+
+/// 根据编译时选择的后端创建默认的`Reactor`
+#[cfg(not(feature = "io_uring"))]
+pub(crate) fn new_reactor() -> Box<dyn Reactor> {
+    Box::new(epoll::EpollReactor::new())
+}
+
+/// 根据编译时选择的后端创建默认的`Reactor`
+#[cfg(feature = "io_uring")]
+pub(crate) fn new_reactor() -> Box<dyn Reactor> {
+    Box::new(io_uring::IoUringReactor::new())
+}
+
+/// 堆里的一个定时器条目，按`deadline`排序，`deadline`相同时再按`id`排序
+struct TimerEntry {
+    deadline: Instant,
+    id: TimerId,
+    waker: Waker,
+}
+
+impl PartialEq for TimerEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.deadline == other.deadline && self.id == other.id
+    }
+}
+
+impl Eq for TimerEntry {}
+
+impl PartialOrd for TimerEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TimerEntry {
+    /// `BinaryHeap`是大顶堆，这里反转比较顺序让最早到期的`deadline`停在堆顶
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .deadline
+            .cmp(&self.deadline)
+            .then_with(|| other.id.cmp(&self.id))
+    }
+}
+
+/// 定时器堆，`epoll`和`io_uring`两种后端共用，避免各自重复实现一遍
+struct TimerWheel {
+    timers: RefCell<BinaryHeap<TimerEntry>>,
+    next_timer_id: RefCell<TimerId>,
+}
+
+impl TimerWheel {
+    fn new() -> Self {
+        Self {
+            timers: RefCell::new(BinaryHeap::new()),
+            next_timer_id: RefCell::new(0),
+        }
+    }
+
+    /// 注册一个在`deadline`到期时需要被唤醒的定时器
+    fn register(&self, deadline: Instant, waker: Waker) -> TimerId {
+        let id = {
+            let mut next = self.next_timer_id.borrow_mut();
+            let id = *next;
+            *next += 1;
+            id
+        };
+        self.timers.borrow_mut().push(TimerEntry {
+            deadline,
+            id,
+            waker,
+        });
+        id
+    }
+
+    /// 计算下一次`wait`应该使用的超时时间：堆为空就无限等待，最近的`deadline`已过去就立刻返回
+    fn next_timeout(&self) -> Option<Duration> {
+        self.timers.borrow().peek().map(|t| {
+            let now = Instant::now();
+            t.deadline.saturating_duration_since(now)
+        })
+    }
+
+    /// 撤销一个还没到期的定时器；堆不支持按key直接删除，重建一份去掉对应条目的堆
+    fn cancel(&self, id: TimerId) {
+        let mut timers = self.timers.borrow_mut();
+        if !timers.iter().any(|t| t.id == id) {
+            return;
+        }
+        *timers = timers.drain().filter(|t| t.id != id).collect();
+    }
+
+    /// 弹出所有已经到期的定时器并唤醒它们对应的`Task`
+    fn fire_expired(&self) {
+        let now = Instant::now();
+        let mut timers = self.timers.borrow_mut();
+        while matches!(timers.peek(), Some(t) if t.deadline <= now) {
+            let entry = timers.pop().unwrap();
+            entry.waker.wake();
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! 异步TCP：默认基于`epoll`的就绪模型，读写时借用调用者的buffer；
+//! 开启`io_uring` feature后额外提供一套转移buffer所有权的`*_owned` API，配合完成模型使用。
+
+use futures::Future;
+use std::{
+    io::{self, Read as _, Write as _},
+    net::{SocketAddr, TcpListener as StdTcpListener, TcpStream as StdTcpStream, ToSocketAddrs},
+    os::unix::io::{AsRawFd, RawFd},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::executor::EX;
+use crate::reactor::{fd_token, IoOp};
+
+/// 异步的`TcpListener`
+pub struct TcpListener {
+    inner: StdTcpListener,
+}
+
+impl TcpListener {
+    /// 绑定地址并监听，自动切换成非阻塞模式
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let inner = StdTcpListener::bind(addr)?;
+        inner.set_nonblocking(true)?;
+        Ok(Self { inner })
+    }
+
+    /// 接受一个新连接
+    pub fn accept(&self) -> Accept<'_> {
+        Accept {
+            listener: &self.inner,
+            registered: false,
+        }
+    }
+}
+
+/// `TcpListener::accept`返回的`Future`
+pub struct Accept<'a> {
+    listener: &'a StdTcpListener,
+    registered: bool,
+}
+
+impl<'a> Future for Accept<'a> {
+    type Output = io::Result<(TcpStream, SocketAddr)>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.listener.accept() {
+            Ok((stream, addr)) => match stream.set_nonblocking(true) {
+                Ok(()) => Poll::Ready(Ok((TcpStream::new(stream), addr))),
+                Err(e) => Poll::Ready(Err(e)),
+            },
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if !self.registered {
+                    let fd = self.listener.as_raw_fd();
+                    let token = fd_token(fd, false);
+                    let submitted = EX.with(|ex| {
+                        ex.reactor.borrow().submit(
+                            IoOp::Read {
+                                fd,
+                                buf: Vec::new(),
+                            },
+                            token,
+                            cx.waker().clone(),
+                        )
+                    });
+                    if let Err(e) = submitted {
+                        return Poll::Ready(Err(e));
+                    }
+                    self.registered = true;
+                }
+                Poll::Pending
+            }
+            Err(e) => Poll::Ready(Err(e)),
+        }
+    }
+}
+
+/// 异步的`TcpStream`
+pub struct TcpStream {
+    inner: StdTcpStream,
+}
+
+impl TcpStream {
+    fn new(inner: StdTcpStream) -> Self {
+        Self { inner }
+    }
+
+    /// 连接到对端地址，自动切换成非阻塞模式
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let inner = StdTcpStream::connect(addr)?;
+        inner.set_nonblocking(true)?;
+        Ok(Self::new(inner))
+    }
+
+    /// 读取数据到`buf`里，借用调用者的buffer
+    pub fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> Read<'a> {
+        Read {
+            stream: self,
+            buf,
+            registered: false,
+        }
+    }
+
+    /// 把`buf`里的数据写出去，借用调用者的buffer
+    pub fn write<'a>(&'a mut self, buf: &'a [u8]) -> Write<'a> {
+        Write {
+            stream: self,
+            buf,
+            registered: false,
+        }
+    }
+}
+
+/// `TcpStream::read`返回的`Future`
+pub struct Read<'a> {
+    stream: &'a TcpStream,
+    buf: &'a mut [u8],
+    registered: bool,
+}
+
+impl<'a> Future for Read<'a> {
+    type Output = io::Result<usize>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match (&self.stream.inner).read(&mut *self.buf) {
+            Ok(n) => Poll::Ready(Ok(n)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if !self.registered {
+                    let fd = self.stream.inner.as_raw_fd();
+                    let token = fd_token(fd, false);
+                    let submitted = EX.with(|ex| {
+                        ex.reactor.borrow().submit(
+                            IoOp::Read {
+                                fd,
+                                buf: Vec::new(),
+                            },
+                            token,
+                            cx.waker().clone(),
+                        )
+                    });
+                    if let Err(e) = submitted {
+                        return Poll::Ready(Err(e));
+                    }
+                    self.registered = true;
+                }
+                Poll::Pending
+            }
+            Err(e) => Poll::Ready(Err(e)),
+        }
+    }
+}
+
+/// `TcpStream::write`返回的`Future`
+pub struct Write<'a> {
+    stream: &'a TcpStream,
+    buf: &'a [u8],
+    registered: bool,
+}
+
+impl<'a> Future for Write<'a> {
+    type Output = io::Result<usize>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match (&self.stream.inner).write(self.buf) {
+            Ok(n) => Poll::Ready(Ok(n)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if !self.registered {
+                    let fd = self.stream.inner.as_raw_fd();
+                    let token = fd_token(fd, true);
+                    let submitted = EX.with(|ex| {
+                        ex.reactor.borrow().submit(
+                            IoOp::Write {
+                                fd,
+                                buf: Vec::new(),
+                            },
+                            token,
+                            cx.waker().clone(),
+                        )
+                    });
+                    if let Err(e) = submitted {
+                        return Poll::Ready(Err(e));
+                    }
+                    self.registered = true;
+                }
+                Poll::Pending
+            }
+            Err(e) => Poll::Ready(Err(e)),
+        }
+    }
+}
+
+#[cfg(feature = "io_uring")]
+impl TcpStream {
+    /// `io_uring`后端下的读取：buffer的所有权转移给内核，完成后连同结果一起还回来
+    pub fn read_owned(&self, buf: Vec<u8>) -> ReadOwned {
+        ReadOwned {
+            fd: self.inner.as_raw_fd(),
+            buf: Some(buf),
+            submitted: false,
+        }
+    }
+
+    /// `io_uring`后端下的写入：同样需要转移buffer的所有权，结果到达后buffer才物归原主
+    pub fn write_owned(&self, buf: Vec<u8>) -> WriteOwned {
+        WriteOwned {
+            fd: self.inner.as_raw_fd(),
+            buf: Some(buf),
+            submitted: false,
+        }
+    }
+}
+
+/// `TcpStream::read_owned`返回的`Future`
+#[cfg(feature = "io_uring")]
+pub struct ReadOwned {
+    fd: RawFd,
+    buf: Option<Vec<u8>>,
+    submitted: bool,
+}
+
+#[cfg(feature = "io_uring")]
+impl Future for ReadOwned {
+    type Output = io::Result<(Vec<u8>, usize)>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let token = fd_token(self.fd, false);
+        if let Some((result, buf)) = EX.with(|ex| ex.reactor.borrow().take_completed(token)) {
+            return Poll::Ready(result.map(|n| (buf, n)));
+        }
+
+        if !self.submitted {
+            let fd = self.fd;
+            let buf = self
+                .buf
+                .take()
+                .expect("ReadOwned polled again after completion");
+            let submitted = EX.with(|ex| {
+                ex.reactor
+                    .borrow()
+                    .submit(IoOp::Read { fd, buf }, token, cx.waker().clone())
+            });
+            if let Err(e) = submitted {
+                return Poll::Ready(Err(e));
+            }
+            self.submitted = true;
+        }
+        Poll::Pending
+    }
+}
+
+/// `TcpStream::write_owned`返回的`Future`
+#[cfg(feature = "io_uring")]
+pub struct WriteOwned {
+    fd: RawFd,
+    buf: Option<Vec<u8>>,
+    submitted: bool,
+}
+
+#[cfg(feature = "io_uring")]
+impl Future for WriteOwned {
+    type Output = io::Result<(Vec<u8>, usize)>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let token = fd_token(self.fd, true);
+        if let Some((result, buf)) = EX.with(|ex| ex.reactor.borrow().take_completed(token)) {
+            return Poll::Ready(result.map(|n| (buf, n)));
+        }
+
+        if !self.submitted {
+            let fd = self.fd;
+            let buf = self
+                .buf
+                .take()
+                .expect("WriteOwned polled again after completion");
+            let submitted = EX.with(|ex| {
+                ex.reactor
+                    .borrow()
+                    .submit(IoOp::Write { fd, buf }, token, cx.waker().clone())
+            });
+            if let Err(e) = submitted {
+                return Poll::Ready(Err(e));
+            }
+            self.submitted = true;
+        }
+        Poll::Pending
+    }
+}